@@ -0,0 +1,2 @@
+pub mod multieq;
+pub mod util;
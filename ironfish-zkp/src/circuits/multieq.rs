@@ -0,0 +1,199 @@
+//! A `ConstraintSystem` wrapper that batches many narrow boolean
+//! equalities (such as the 32-bit XOR/addmany checks inside a Blake2s
+//! round) into a single field-element constraint, so gadgets built on
+//! top of it don't pay one R1CS constraint per equality.
+//!
+//! TODO(ironfish-zkp#multieq-blake2s): this crate slice doesn't contain
+//! the Blake2s circuit that `asset_info_preimage`'s hash step depends on,
+//! so nothing here routes through `MultiEq` yet and the proving-time win
+//! this was added for isn't realized by this crate alone. Whoever owns
+//! the Blake2s gadget (wherever it lives in the workspace) needs to wrap
+//! its round function's `ConstraintSystem` in a `MultiEq::new(cs)` at the
+//! top of the round loop, batching each round's XOR/addmany equalities
+//! through `enforce_equal` instead of constraining them directly.
+
+use ff::PrimeField;
+
+use bellman::{ConstraintSystem, LinearCombination, SynthesisError, Variable};
+
+pub struct MultiEq<Scalar: PrimeField, CS: ConstraintSystem<Scalar>> {
+    cs: CS,
+    ops: usize,
+    bits_used: usize,
+    lhs: LinearCombination<Scalar>,
+    rhs: LinearCombination<Scalar>,
+}
+
+impl<Scalar: PrimeField, CS: ConstraintSystem<Scalar>> MultiEq<Scalar, CS> {
+    pub fn new(cs: CS) -> Self {
+        MultiEq {
+            cs,
+            ops: 0,
+            bits_used: 0,
+            lhs: LinearCombination::zero(),
+            rhs: LinearCombination::zero(),
+        }
+    }
+
+    fn accumulate(&mut self) {
+        let ops = self.ops;
+        let lhs = self.lhs.clone();
+        let rhs = self.rhs.clone();
+        self.cs.enforce(
+            || format!("multieq {}", ops),
+            |_| lhs,
+            |lc| lc + CS::one(),
+            |_| rhs,
+        );
+
+        self.lhs = LinearCombination::zero();
+        self.rhs = LinearCombination::zero();
+        self.bits_used = 0;
+        self.ops += 1;
+    }
+
+    /// Folds the equality `lhs == rhs` (over `num_bits` bits) into the
+    /// running accumulators, flushing the previous batch first if
+    /// there's no more room for it in a single field element.
+    pub fn enforce_equal(
+        &mut self,
+        num_bits: usize,
+        lhs: &LinearCombination<Scalar>,
+        rhs: &LinearCombination<Scalar>,
+    ) {
+        // Check if we will exceed the capacity of the field element by
+        // adding this equality
+        if (Scalar::CAPACITY as usize) <= (self.bits_used + num_bits) {
+            self.accumulate();
+        }
+
+        assert!((Scalar::CAPACITY as usize) > (self.bits_used + num_bits));
+
+        let coeff = Scalar::from(2).pow_vartime([self.bits_used as u64]);
+        self.lhs = self.lhs.clone() + (coeff, lhs);
+        self.rhs = self.rhs.clone() + (coeff, rhs);
+        self.bits_used += num_bits;
+    }
+}
+
+impl<Scalar: PrimeField, CS: ConstraintSystem<Scalar>> Drop for MultiEq<Scalar, CS> {
+    fn drop(&mut self) {
+        if self.bits_used > 0 {
+            self.accumulate();
+        }
+    }
+}
+
+impl<Scalar: PrimeField, CS: ConstraintSystem<Scalar>> ConstraintSystem<Scalar>
+    for MultiEq<Scalar, CS>
+{
+    type Root = Self;
+
+    fn one() -> Variable {
+        CS::one()
+    }
+
+    fn alloc<F, A, AR>(&mut self, annotation: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<Scalar, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.cs.alloc(annotation, f)
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, annotation: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<Scalar, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.cs.alloc_input(annotation, f)
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, annotation: A, a: LA, b: LB, c: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<Scalar>) -> LinearCombination<Scalar>,
+        LB: FnOnce(LinearCombination<Scalar>) -> LinearCombination<Scalar>,
+        LC: FnOnce(LinearCombination<Scalar>) -> LinearCombination<Scalar>,
+    {
+        self.cs.enforce(annotation, a, b, c)
+    }
+
+    fn push_namespace<NR, N>(&mut self, name_fn: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+        self.cs.get_root().push_namespace(name_fn)
+    }
+
+    fn pop_namespace(&mut self) {
+        self.cs.get_root().pop_namespace()
+    }
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bellman::gadgets::test::TestConstraintSystem;
+    use bellman::{ConstraintSystem, LinearCombination};
+
+    use super::MultiEq;
+
+    #[test]
+    fn enforce_equal_packs_several_equalities_into_one_constraint() {
+        let mut cs = TestConstraintSystem::<bls12_381::Scalar>::new();
+
+        {
+            let mut cs = MultiEq::new(&mut cs);
+
+            for i in 0..4 {
+                let a = cs
+                    .alloc(|| format!("a{}", i), || Ok(bls12_381::Scalar::from(5u64)))
+                    .unwrap();
+                let b = cs
+                    .alloc(|| format!("b{}", i), || Ok(bls12_381::Scalar::from(5u64)))
+                    .unwrap();
+
+                cs.enforce_equal(
+                    8,
+                    &(LinearCombination::zero() + a),
+                    &(LinearCombination::zero() + b),
+                );
+            }
+        }
+
+        assert!(cs.is_satisfied());
+        assert_eq!(cs.num_constraints(), 1);
+    }
+
+    #[test]
+    fn enforce_equal_rejects_mismatched_values() {
+        let mut cs = TestConstraintSystem::<bls12_381::Scalar>::new();
+
+        {
+            let mut cs = MultiEq::new(&mut cs);
+
+            let a = cs
+                .alloc(|| "a", || Ok(bls12_381::Scalar::from(5u64)))
+                .unwrap();
+            let b = cs
+                .alloc(|| "b", || Ok(bls12_381::Scalar::from(6u64)))
+                .unwrap();
+
+            cs.enforce_equal(
+                8,
+                &(LinearCombination::zero() + a),
+                &(LinearCombination::zero() + b),
+            );
+        }
+
+        assert!(!cs.is_satisfied());
+    }
+}
@@ -4,7 +4,7 @@ use bellman::{
     gadgets::boolean::{self, AllocatedBit, Boolean},
     ConstraintSystem, SynthesisError,
 };
-use ff::PrimeField;
+use ff::{PrimeField, PrimeFieldBits};
 use zcash_primitives::sapling::ValueCommitment;
 use zcash_proofs::{
     circuit::ecc::{self, EdwardsPoint},
@@ -26,7 +26,11 @@ pub fn asset_info_preimage<CS: bellman::ConstraintSystem<bls12_381::Scalar>>(
     combined_preimage
         .extend(asset_public_key.repr(cs.namespace(|| "booleanize asset_public_key"))?);
 
-    let name_bits = slice_into_boolean_vec_le(cs.namespace(|| "booleanize name"), Some(name), 32)?;
+    // `name` is exactly one 32-byte chunk, so unlike `metadata` it fits
+    // within a single field element and must be canonical before it's
+    // re-packed into one for hashing.
+    let name_bits =
+        slice_into_boolean_vec_le_strict(cs.namespace(|| "booleanize name"), Some(name), 32)?;
     combined_preimage.extend(name_bits);
 
     let metadata_bits =
@@ -43,10 +47,33 @@ pub fn asset_info_preimage<CS: bellman::ConstraintSystem<bls12_381::Scalar>>(
     Ok(combined_preimage)
 }
 
-pub fn slice_into_boolean_vec_le<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
+pub fn slice_into_boolean_vec_le<Scalar: PrimeFieldBits, CS: ConstraintSystem<Scalar>>(
+    cs: CS,
+    value: Option<&[u8]>,
+    byte_length: u32,
+) -> Result<Vec<Boolean>, SynthesisError> {
+    slice_into_boolean_vec_le_inner(cs, value, byte_length, false)
+}
+
+/// Like [`slice_into_boolean_vec_le`], but additionally enforces that the
+/// allocated bits, read as a little-endian scalar, are strictly less
+/// than `Scalar`'s modulus. Use this for byte slices that are meant to be
+/// re-packed into a field element later (e.g. before hashing), where an
+/// unconstrained decomposition would let a malicious prover slip in a
+/// non-canonical representative.
+pub fn slice_into_boolean_vec_le_strict<Scalar: PrimeFieldBits, CS: ConstraintSystem<Scalar>>(
+    cs: CS,
+    value: Option<&[u8]>,
+    byte_length: u32,
+) -> Result<Vec<Boolean>, SynthesisError> {
+    slice_into_boolean_vec_le_inner(cs, value, byte_length, true)
+}
+
+fn slice_into_boolean_vec_le_inner<Scalar: PrimeFieldBits, CS: ConstraintSystem<Scalar>>(
     mut cs: CS,
     value: Option<&[u8]>,
     byte_length: u32,
+    strict: bool,
 ) -> Result<Vec<Boolean>, SynthesisError> {
     let bit_length = byte_length * 8;
     let values: Vec<Option<bool>> = match value {
@@ -73,31 +100,120 @@ pub fn slice_into_boolean_vec_le<Scalar: PrimeField, CS: ConstraintSystem<Scalar
         return Err(SynthesisError::Unsatisfiable);
     }
 
+    if strict {
+        enforce_canonical_le(cs.namespace(|| "enforce canonical encoding"), &bits)?;
+    }
+
     Ok(bits)
 }
 
-/// Exposes a Pedersen commitment to the value as an
-/// input to the circuit
-pub fn expose_value_commitment<CS>(
+/// Enforces that `bits`, read as a little-endian scalar, is strictly less
+/// than `Scalar`'s modulus.
+///
+/// Walks the modulus from its most-significant bit down, maintaining a
+/// boolean `matched_so_far` that is true as long as every witness bit
+/// seen so far is identical to the modulus's. At any position where the
+/// modulus has a 0, the witness bit there is forced to 0 as well whenever
+/// `matched_so_far` holds, which rules out the witness ever overtaking
+/// the modulus from that point on. Once every bit has been walked,
+/// `matched_so_far` being true means the witness equals the modulus
+/// exactly; that's forbidden too, since this enforces strict `<`, not
+/// `<=` (the modulus itself re-packs to the same field element as zero).
+fn enforce_canonical_le<Scalar: PrimeFieldBits, CS: ConstraintSystem<Scalar>>(
     mut cs: CS,
-    value_commitment: Option<ValueCommitment>,
+    bits: &[Boolean],
+) -> Result<(), SynthesisError> {
+    let modulus_bits: Vec<bool> = Scalar::char_le_bits().into_iter().collect();
+    if modulus_bits.len() < bits.len() {
+        // Not the best error type here, but easier than forking the error types right now
+        return Err(SynthesisError::Unsatisfiable);
+    }
+
+    // The witness is implicitly zero above its own width, so it only still
+    // matches the modulus there if the modulus is zero there too.
+    let mut matched_so_far = Boolean::constant(modulus_bits[bits.len()..].iter().all(|&b| !b));
+
+    for (i, bit) in bits.iter().enumerate().rev() {
+        if modulus_bits[i] {
+            // The modulus has a 1 here; the match only continues if the
+            // witness bit does too.
+            matched_so_far = Boolean::and(
+                cs.namespace(|| format!("match continues at bit {}", i)),
+                &matched_so_far,
+                bit,
+            )?;
+        } else {
+            // The modulus has a 0 here. If every bit above matched the
+            // modulus exactly, this bit must be 0 too, or the witness
+            // would already exceed the modulus.
+            let matches_and_set = Boolean::and(
+                cs.namespace(|| format!("still matching and set at bit {}", i)),
+                &matched_so_far,
+                bit,
+            )?;
+            Boolean::enforce_equal(
+                cs.namespace(|| format!("bit {} is canonical", i)),
+                &matches_and_set,
+                &Boolean::constant(false),
+            )?;
+        }
+    }
+
+    // Reject the one witness the loop above lets through unchallenged:
+    // an exact bit-for-bit match with the modulus.
+    Boolean::enforce_equal(
+        cs.namespace(|| "witness does not equal the modulus"),
+        &matched_so_far,
+        &Boolean::constant(false),
+    )?;
+
+    Ok(())
+}
+
+/// Booleanizes `value` into little-endian bits, one per position of a
+/// `u64`, forcing every bit to zero when `is_dummy` is set. This is used
+/// to pad a fixed-size batch of spends/outputs with decoy notes whose
+/// value commitment must provably commit to zero.
+///
+/// Each bit goes through [`AllocatedBit::alloc_conditionally`], which
+/// folds the usual `(1 - b) * b = 0` boolean constraint and the
+/// dummy-forces-zero constraint into the single constraint
+/// `(1 - is_dummy - b) * b = 0` rather than emitting both, so non-dummy
+/// notes (the overwhelming majority of Spends/Outputs) don't pay for a
+/// constraint they don't need.
+fn value_into_boolean_vec_le_conditionally<CS>(
+    mut cs: CS,
+    value: Option<u64>,
+    is_dummy: &AllocatedBit,
 ) -> Result<Vec<boolean::Boolean>, SynthesisError>
 where
     CS: ConstraintSystem<bls12_381::Scalar>,
 {
-    // Booleanize the value into little-endian bit order
-    let value_bits = boolean::u64_into_boolean_vec_le(
-        cs.namespace(|| "value"),
-        value_commitment.as_ref().map(|c| c.value),
-    )?;
-
-    // Compute the note value in the exponent
-    let value = ecc::fixed_base_multiplication(
-        cs.namespace(|| "compute the value in the exponent"),
-        &VALUE_COMMITMENT_VALUE_GENERATOR,
-        &value_bits,
-    )?;
+    (0..64)
+        .map(|i| {
+            let bit = value.map(|value| (value >> i) & 1 == 1);
+            Ok(boolean::Boolean::from(AllocatedBit::alloc_conditionally(
+                cs.namespace(|| format!("value bit {}", i)),
+                bit,
+                is_dummy,
+            )?))
+        })
+        .collect()
+}
 
+/// Finishes a value commitment once the value term `value * G` has
+/// already been computed (`G` may be the fixed Sapling value generator or
+/// a per-asset one): booleanizes the randomness, multiplies it by the
+/// fixed randomness generator, adds it to the value term, and exposes the
+/// resulting point as a circuit input.
+fn finish_value_commitment<CS>(
+    mut cs: CS,
+    value: EdwardsPoint,
+    value_commitment: Option<ValueCommitment>,
+) -> Result<(), SynthesisError>
+where
+    CS: ConstraintSystem<bls12_381::Scalar>,
+{
     // Booleanize the randomness. This does not ensure
     // the bit representation is "in the field" because
     // it doesn't matter for security.
@@ -119,7 +235,83 @@ where
     // Expose the commitment as an input to the circuit
     cv.inputize(cs.namespace(|| "commitment point"))?;
 
-    Ok(value_bits)
+    Ok(())
+}
+
+/// Exposes a Pedersen commitment to the value as an
+/// input to the circuit
+///
+/// When `is_dummy` is set, the value bits are constrained to zero (see
+/// [`AllocatedBit::alloc_conditionally`]), so the commitment's value
+/// component is forced to the identity while the randomness term still
+/// hides it. The value bits and the dummy flag are both returned so the
+/// caller can also relax any balance checks for dummy notes.
+pub fn expose_value_commitment<CS>(
+    mut cs: CS,
+    is_dummy: AllocatedBit,
+    value_commitment: Option<ValueCommitment>,
+) -> Result<(Vec<boolean::Boolean>, AllocatedBit), SynthesisError>
+where
+    CS: ConstraintSystem<bls12_381::Scalar>,
+{
+    // Booleanize the value into little-endian bit order, forcing it to
+    // zero when this is a dummy/padding note
+    let value_bits = value_into_boolean_vec_le_conditionally(
+        cs.namespace(|| "value"),
+        value_commitment.as_ref().map(|c| c.value),
+        &is_dummy,
+    )?;
+
+    // Compute the note value in the exponent
+    let value = ecc::fixed_base_multiplication(
+        cs.namespace(|| "compute the value in the exponent"),
+        &VALUE_COMMITMENT_VALUE_GENERATOR,
+        &value_bits,
+    )?;
+
+    finish_value_commitment(cs, value, value_commitment)?;
+
+    Ok((value_bits, is_dummy))
+}
+
+/// Like [`expose_value_commitment`], but computes the value term against
+/// an asset-specific value generator rather than the fixed Sapling value
+/// generator, so a single Spend/Output circuit can prove balance across
+/// commitments to heterogeneous asset types: `cv = value * asset_generator
+/// + rcv * VALUE_COMMITMENT_RANDOMNESS_GENERATOR`.
+///
+/// `asset_generator` is witnessed by the caller (it's derived per-asset by
+/// hashing to the curve outside the circuit), so it's constrained here to
+/// be a non-small-order point before it's used as a value base.
+pub fn expose_value_commitment_with_asset_generator<CS>(
+    mut cs: CS,
+    asset_generator: EdwardsPoint,
+    is_dummy: AllocatedBit,
+    value_commitment: Option<ValueCommitment>,
+) -> Result<(Vec<boolean::Boolean>, AllocatedBit), SynthesisError>
+where
+    CS: ConstraintSystem<bls12_381::Scalar>,
+{
+    asset_generator.assert_not_small_order(cs.namespace(|| "asset generator not small order"))?;
+
+    // Booleanize the value into little-endian bit order, forcing it to
+    // zero when this is a dummy/padding note
+    let value_bits = value_into_boolean_vec_le_conditionally(
+        cs.namespace(|| "value"),
+        value_commitment.as_ref().map(|c| c.value),
+        &is_dummy,
+    )?;
+
+    // Compute the note value in the exponent, using variable-base
+    // multiplication since the generator is per-asset rather than fixed
+    let value = asset_generator.mul(
+        cs.namespace(|| "compute the value in the exponent"),
+        &value_bits,
+    )?;
+
+    finish_value_commitment(cs, value, value_commitment)?;
+
+    Ok((value_bits, is_dummy))
 }
 
 pub fn expose_randomized_public_key(
@@ -148,3 +340,115 @@ pub fn expose_randomized_public_key(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use bellman::gadgets::{boolean::AllocatedBit, test::TestConstraintSystem};
+    use bellman::{ConstraintSystem, SynthesisError};
+    use ff::{Field, PrimeFieldBits};
+    use group::Group;
+    use zcash_primitives::sapling::ValueCommitment;
+    use zcash_proofs::circuit::ecc;
+
+    use super::{
+        expose_value_commitment, expose_value_commitment_with_asset_generator,
+        slice_into_boolean_vec_le_strict,
+    };
+
+    fn modulus_le_bytes() -> Vec<u8> {
+        bls12_381::Scalar::char_le_bits()
+            .into_iter()
+            .collect::<Vec<bool>>()
+            .chunks(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (i, &bit)| byte | ((bit as u8) << i))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn slice_into_boolean_vec_le_strict_rejects_the_modulus_itself() {
+        let modulus_bytes = modulus_le_bytes();
+
+        let mut cs = TestConstraintSystem::<bls12_381::Scalar>::new();
+        slice_into_boolean_vec_le_strict(&mut cs, Some(&modulus_bytes), modulus_bytes.len() as u32)
+            .unwrap();
+
+        // The modulus re-packs to the same field element as zero, so it
+        // must not be accepted as a canonical encoding.
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn slice_into_boolean_vec_le_strict_accepts_modulus_minus_one() {
+        let mut bytes = modulus_le_bytes();
+        // The modulus is odd (it's prime), so subtracting one from its
+        // least-significant byte can't borrow out of the slice.
+        bytes[0] -= 1;
+
+        let mut cs = TestConstraintSystem::<bls12_381::Scalar>::new();
+        slice_into_boolean_vec_le_strict(&mut cs, Some(&bytes), bytes.len() as u32).unwrap();
+
+        assert!(cs.is_satisfied());
+    }
+
+    #[test]
+    fn expose_value_commitment_rejects_nonzero_value_when_dummy() {
+        let mut cs = TestConstraintSystem::<bls12_381::Scalar>::new();
+
+        let is_dummy = AllocatedBit::alloc(cs.namespace(|| "is_dummy"), Some(true)).unwrap();
+        let value_commitment = ValueCommitment {
+            value: 1,
+            randomness: jubjub::Fr::one(),
+        };
+
+        expose_value_commitment(&mut cs, is_dummy, Some(value_commitment)).unwrap();
+
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn expose_value_commitment_accepts_zero_value_when_dummy() {
+        let mut cs = TestConstraintSystem::<bls12_381::Scalar>::new();
+
+        let is_dummy = AllocatedBit::alloc(cs.namespace(|| "is_dummy"), Some(true)).unwrap();
+        let value_commitment = ValueCommitment {
+            value: 0,
+            randomness: jubjub::Fr::one(),
+        };
+
+        expose_value_commitment(&mut cs, is_dummy, Some(value_commitment)).unwrap();
+
+        assert!(cs.is_satisfied());
+    }
+
+    #[test]
+    fn expose_value_commitment_with_asset_generator_rejects_small_order_generator() {
+        let mut cs = TestConstraintSystem::<bls12_381::Scalar>::new();
+
+        let asset_generator = ecc::EdwardsPoint::witness(
+            cs.namespace(|| "asset generator"),
+            Some(jubjub::ExtendedPoint::identity()),
+        )
+        .unwrap();
+        let is_dummy = AllocatedBit::alloc(cs.namespace(|| "is_dummy"), Some(false)).unwrap();
+        let value_commitment = ValueCommitment {
+            value: 1,
+            randomness: jubjub::Fr::one(),
+        };
+
+        let result = expose_value_commitment_with_asset_generator(
+            &mut cs,
+            asset_generator,
+            is_dummy,
+            Some(value_commitment),
+        );
+
+        // The small-order check fails during synthesis: the identity's `u`
+        // coordinate is zero, so it has no inverse to witness.
+        assert!(matches!(result, Err(SynthesisError::DivisionByZero)));
+    }
+}